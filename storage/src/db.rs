@@ -16,7 +16,7 @@
 #[cfg(feature = "sqlite")]
 mod sql;
 #[cfg(feature = "sqlite")]
-pub use sql::SqlDB;
+pub use sql::{Instrumentation, QueryKind, SqlDB};
 #[cfg(feature = "parity-db")]
 mod paritydb;
 #[cfg(feature = "parity-db")]
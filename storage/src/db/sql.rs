@@ -72,17 +72,104 @@ use rusqlite::{
 use serialize::{Deserializable, Serializable};
 use std::{
     collections::{HashMap, HashSet},
+    fmt,
     fs::{File, OpenOptions},
     marker::PhantomData,
     path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
+/// The kind of db operation a [`Instrumentation::on_query`] event describes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueryKind {
+    /// [`super::DB::get_node`].
+    GetNode,
+    /// [`super::DB::get_unreachable_keys`].
+    GetUnreachableKeys,
+    /// [`super::DB::batch_get_nodes`].
+    BatchGetNodes,
+    /// [`super::DB::insert_node`].
+    InsertNode,
+    /// [`super::DB::delete_node`].
+    DeleteNode,
+    /// [`super::DB::size`].
+    Size,
+    /// [`super::DB::get_root_count`].
+    GetRootCount,
+    /// [`super::DB::set_root_count`].
+    SetRootCount,
+    /// [`super::DB::get_roots`].
+    GetRoots,
+}
+
+/// Hooks for observing the timing and volume of [`SqlDB`] operations.
+///
+/// This exists so that downstream crates can feed db operations into their
+/// own histograms/counters -- e.g. to diagnose busy-timeout or GC-pause
+/// problems -- without this crate taking a hard dependency on any particular
+/// metrics library. Install one via [`SqlDB::set_instrumentation`].
+///
+/// All methods have a no-op default implementation, so implementors only
+/// need to override the events they care about.
+pub trait Instrumentation: Send + Sync {
+    /// Called after a `batch_update`, with the number of updates applied and
+    /// the time taken.
+    fn on_batch_update(&self, rows: usize, duration: Duration) {
+        let _ = (rows, duration);
+    }
+
+    /// Called after a GC pass (or incremental GC step), with the number of
+    /// candidate keys scanned, the number actually collected, and the time
+    /// taken.
+    fn on_gc(&self, scanned: usize, collected: usize, duration: Duration) {
+        let _ = (scanned, collected, duration);
+    }
+
+    /// Called after a query of kind `kind`, with the time taken.
+    fn on_query(&self, kind: QueryKind, duration: Duration) {
+        let _ = (kind, duration);
+    }
+}
+
+/// The default, no-op [`Instrumentation`].
+#[derive(Debug, Default)]
+struct NoopInstrumentation;
+
+impl Instrumentation for NoopInstrumentation {}
+
+// Forward through `Arc`, so that callers can share an `Instrumentation`
+// instance between a `SqlDB` and code that wants to inspect the events it
+// records (e.g. in tests).
+impl<T: Instrumentation + ?Sized> Instrumentation for Arc<T> {
+    fn on_batch_update(&self, rows: usize, duration: Duration) {
+        (**self).on_batch_update(rows, duration)
+    }
+
+    fn on_gc(&self, scanned: usize, collected: usize, duration: Duration) {
+        (**self).on_gc(scanned, collected, duration)
+    }
+
+    fn on_query(&self, kind: QueryKind, duration: Duration) {
+        (**self).on_query(kind, duration)
+    }
+}
+
 /// A `DB` backed by an SQLite database.
-#[derive(Debug)]
 pub struct SqlDB<H: WellBehavedHasher = DefaultHasher> {
     pool: Pool<SqliteConnectionManager>,
     _phantom: std::marker::PhantomData<H>,
     lock_file: Option<File>,
+    instrumentation: Arc<dyn Instrumentation>,
+}
+
+impl<H: WellBehavedHasher> fmt::Debug for SqlDB<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SqlDB")
+            .field("pool", &self.pool)
+            .field("lock_file", &self.lock_file)
+            .finish()
+    }
 }
 
 impl<H: WellBehavedHasher> Default for SqlDB<H> {
@@ -234,11 +321,31 @@ impl<H: WellBehavedHasher> SqlDB<H> {
             pool: Pool::new(cm.with_init(init)).unwrap(),
             _phantom: PhantomData,
             lock_file,
+            instrumentation: Arc::new(NoopInstrumentation),
         };
         db.create_tables();
         db
     }
 
+    /// Install hooks for observing the timing and volume of db operations.
+    ///
+    /// See [`Instrumentation`] for details.
+    pub fn set_instrumentation(&mut self, instrumentation: impl Instrumentation + 'static) {
+        self.instrumentation = Arc::new(instrumentation);
+    }
+
+    /// Time `closure`, and report it via [`Instrumentation::on_query`] as a
+    /// query of kind `kind`.
+    fn instrument_query<F, R>(&self, kind: QueryKind, closure: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let start = Instant::now();
+        let result = closure();
+        self.instrumentation.on_query(kind, start.elapsed());
+        result
+    }
+
     /// Create database tables and indices if they don't already exist.
     fn create_tables(&self) {
         self.with_tx(Immediate, |tx| {
@@ -266,6 +373,23 @@ impl<H: WellBehavedHasher> SqlDB<H> {
             tx.execute(sql, ()).unwrap();
             let sql = "CREATE INDEX IF NOT EXISTS ix_root_count ON root (count)";
             tx.execute(sql, ()).unwrap();
+            // Candidate keys for incremental GC, i.e. keys whose `ref_count`
+            // has dropped to zero and are pending processing by `gc_step`.
+            // Like `root.key`, this isn't declared as a foreign key
+            // referencing `node.key`, for the same out-of-order-write
+            // reasons.
+            let sql = "CREATE TABLE IF NOT EXISTS gc_worklist (
+                     key BLOB NOT NULL PRIMARY KEY
+                   )";
+            tx.execute(sql, ()).unwrap();
+            // The `additional_roots` of the incremental GC currently in
+            // progress (if any), persisted so that `gc_step` can honor them
+            // for the whole sweep, not just at `gc_seed_worklist` time. See
+            // `gc_seed_worklist`.
+            let sql = "CREATE TABLE IF NOT EXISTS gc_extra_roots (
+                     key BLOB NOT NULL PRIMARY KEY
+                   )";
+            tx.execute(sql, ()).unwrap();
         })
     }
 
@@ -328,6 +452,9 @@ impl<H: WellBehavedHasher> SqlDB<H> {
     /// crash which left the db in an inconsistent state, in terms of db-stored
     /// reference counts.
     fn _gc(&mut self, additional_roots: HashSet<ArenaKey<H>>) {
+        let start = Instant::now();
+        let mut scanned = 0;
+        let mut collected = 0;
         self.with_tx(Immediate, |tx| {
             // Select keys that are not roots and have a `ref_count` of 0.
             let sql =
@@ -364,6 +491,7 @@ impl<H: WellBehavedHasher> SqlDB<H> {
                 if unreachable_keys.is_empty() {
                     break;
                 }
+                scanned += unreachable_keys.len();
                 for key in unreachable_keys {
                     let children: Vec<ArenaKey<H>> = get_children
                         .query_row(params![key.clone()], |row| {
@@ -375,6 +503,7 @@ impl<H: WellBehavedHasher> SqlDB<H> {
                         dec_ref_count.execute(params![child]).unwrap();
                     }
                     delete_node.execute(params![key]).unwrap();
+                    collected += 1;
                 }
             }
 
@@ -382,7 +511,191 @@ impl<H: WellBehavedHasher> SqlDB<H> {
             get_children.finalize().unwrap();
             dec_ref_count.finalize().unwrap();
             delete_node.finalize().unwrap();
-        })
+        });
+        self.instrumentation.on_gc(scanned, collected, start.elapsed());
+    }
+
+    /// Seed the incremental GC worklist with all currently unreachable keys.
+    ///
+    /// This is the entry point for incremental GC: call this once to
+    /// populate `gc_worklist`, then call [`Self::gc_step`] repeatedly (e.g.
+    /// interleaved with normal operation) until the worklist is drained, to
+    /// process the same keys `_gc` would, but in small, bounded chunks rather
+    /// than one atomic sweep.
+    ///
+    /// `additional_roots` behaves as in `_gc`: keys in it, and their
+    /// descendants, are never collected, in addition to the roots already
+    /// marked in the DB. Unlike `_gc`'s `additional_roots` argument (which
+    /// only applies to that one call), `additional_roots` here is persisted
+    /// in `gc_extra_roots` for the duration of the incremental sweep, since
+    /// `gc_step` must keep honoring it across many separate transactions,
+    /// including for children discovered after seeding.
+    ///
+    /// Must not be called again until the previous sweep has fully drained
+    /// (the last `gc_step` call returned 0) -- panics otherwise, since
+    /// replacing `gc_extra_roots` out from under a still-pending worklist
+    /// would silently drop that worklist's protection for keys that were
+    /// only safe via the old `additional_roots`.
+    ///
+    /// # Note
+    ///
+    /// Like `_gc`, this reads `node`/`root` directly, bypassing any wrapping
+    /// write-cache, and assumes the back-end has no pending writes. A
+    /// backend-aware GC implementation is provided by
+    /// [`crate::backend::StorageBackend::gc`]; this is a lower-level building
+    /// block, not a substitute for it.
+    pub(crate) fn gc_seed_worklist(&mut self, additional_roots: &HashSet<ArenaKey<H>>) {
+        let start = Instant::now();
+        let scanned = self.with_tx(Immediate, |tx| {
+            let pending: bool = tx
+                .query_row("SELECT 1 FROM gc_worklist LIMIT 1", (), |row| row.get(0))
+                .optional()
+                .unwrap()
+                .unwrap_or(false);
+            assert!(
+                !pending,
+                "gc_seed_worklist called with a previous sweep still undrained"
+            );
+
+            tx.execute("DELETE FROM gc_extra_roots", ()).unwrap();
+            let sql = "INSERT OR IGNORE INTO gc_extra_roots (key) VALUES (?1)";
+            let mut insert_extra_root = tx.prepare(sql).unwrap();
+            for key in additional_roots {
+                insert_extra_root.execute(params![key.clone()]).unwrap();
+            }
+            insert_extra_root.finalize().unwrap();
+
+            let sql = "INSERT OR IGNORE INTO gc_worklist (key)
+                       SELECT key FROM node
+                       WHERE key NOT IN (SELECT key FROM root)
+                         AND key NOT IN (SELECT key FROM gc_extra_roots)
+                         AND ref_count = 0";
+            tx.execute(sql, ()).unwrap()
+        });
+        // Reuse the `on_gc` hook for worklist seeding too: `scanned` reports
+        // how many keys were enqueued as GC candidates, with `collected` at 0
+        // since seeding never deletes anything itself.
+        self.instrumentation.on_gc(scanned, 0, start.elapsed());
+    }
+
+    /// Process up to `budget` keys from the incremental GC worklist, in a
+    /// single short write transaction. `budget` must be greater than zero --
+    /// a zero budget would pop nothing and return 0 regardless of how much
+    /// work remains, which would otherwise look indistinguishable from a
+    /// drained worklist. Returns the number of keys popped from the worklist
+    /// (which is 0 exactly when the worklist was already empty, regardless of
+    /// how many of those keys turned out to still be unreferenced -- see
+    /// below). Callers should keep calling `gc_step` until it returns 0, at
+    /// which point the worklist is fully drained.
+    ///
+    /// For each popped key, if it's still unreferenced (`ref_count <= 0`,
+    /// not a GC root, and not in `gc_extra_roots` -- all of which may have
+    /// changed since the key was enqueued, since normal operation is
+    /// expected to interleave with `gc_step` calls), it's deleted, the
+    /// `ref_count` of its children is decremented, and any child whose
+    /// `ref_count` drops to zero is enqueued in turn. Otherwise, it's simply
+    /// dropped from the worklist -- a popped-but-no-longer-unreferenced key
+    /// does *not* mean the worklist is empty, so callers must not treat a
+    /// step that collects nothing as a stopping condition.
+    ///
+    /// This turns the stop-the-world sweep of `_gc` into many short write
+    /// transactions that interleave with normal operation, bounding the time
+    /// any single `Immediate` transaction holds the write lock, while still
+    /// converging to the same result as `_gc` once [`Self::gc_seed_worklist`]
+    /// has been called and `gc_step` is called repeatedly until it returns 0.
+    ///
+    /// # Note
+    ///
+    /// Like `_gc`, this mutates `ref_count`/`node` directly, bypassing any
+    /// wrapping write-cache, and assumes the back-end has no pending writes.
+    /// A backend-aware GC implementation is provided by
+    /// [`crate::backend::StorageBackend::gc`]; this is a lower-level building
+    /// block, not a substitute for it.
+    pub(crate) fn gc_step(&mut self, budget: usize) -> usize {
+        assert!(budget > 0, "gc_step budget must be greater than zero");
+        let start = Instant::now();
+        let (scanned, collected) = self.with_tx(Immediate, |tx| {
+            let sql = "SELECT key FROM gc_worklist LIMIT (?1)";
+            let mut pop_worklist = tx.prepare(sql).unwrap();
+            let sql = "DELETE FROM gc_worklist WHERE key = (?1)";
+            let mut remove_from_worklist = tx.prepare(sql).unwrap();
+            // Read as `i64`, since a key processed more than once in the same
+            // step (because it's referenced by more than one other key also
+            // being collected this step) may transiently go negative.
+            let sql = "SELECT ref_count FROM node WHERE key = (?1)";
+            let mut get_ref_count = tx.prepare(sql).unwrap();
+            let sql = "SELECT 1 FROM root WHERE key = (?1)
+                       UNION SELECT 1 FROM gc_extra_roots WHERE key = (?1)";
+            let mut is_root = tx.prepare(sql).unwrap();
+            let sql = "SELECT children FROM node WHERE key = (?1)";
+            let mut get_children = tx.prepare(sql).unwrap();
+            let sql = "UPDATE node SET ref_count = ref_count - 1 WHERE key = (?1)";
+            let mut dec_ref_count = tx.prepare(sql).unwrap();
+            let sql = "DELETE FROM node WHERE key = (?1)";
+            let mut delete_node = tx.prepare(sql).unwrap();
+            let sql = "INSERT OR IGNORE INTO gc_worklist (key) VALUES (?1)";
+            let mut enqueue = tx.prepare(sql).unwrap();
+
+            let keys: Vec<ArenaKey<H>> = pop_worklist
+                .query_map(params![budget as i64], |row| {
+                    let key: ArenaKey<H> = row.get(0)?;
+                    Ok(key)
+                })
+                .unwrap()
+                .map(|r| r.unwrap())
+                .collect();
+            let scanned = keys.len();
+
+            let mut collected = 0;
+            for key in keys {
+                remove_from_worklist.execute(params![key.clone()]).unwrap();
+
+                let ref_count: Option<i64> = get_ref_count
+                    .query_row(params![key.clone()], |row| row.get(0))
+                    .optional()
+                    .unwrap();
+                let is_rooted = is_root
+                    .query_row(params![key.clone()], |row| row.get::<_, i64>(0))
+                    .optional()
+                    .unwrap()
+                    .is_some();
+                if is_rooted || ref_count.is_none_or(|rc| rc > 0) {
+                    continue;
+                }
+
+                let children: Vec<ArenaKey<H>> = get_children
+                    .query_row(params![key.clone()], |row| {
+                        let children: Children<H> = row.get(0)?;
+                        Ok(children.0)
+                    })
+                    .unwrap();
+                for child in children {
+                    dec_ref_count.execute(params![child.clone()]).unwrap();
+                    let child_ref_count: Option<i64> = get_ref_count
+                        .query_row(params![child.clone()], |row| row.get(0))
+                        .optional()
+                        .unwrap();
+                    if child_ref_count.is_some_and(|rc| rc <= 0) {
+                        enqueue.execute(params![child]).unwrap();
+                    }
+                }
+                delete_node.execute(params![key]).unwrap();
+                collected += 1;
+            }
+
+            pop_worklist.finalize().unwrap();
+            remove_from_worklist.finalize().unwrap();
+            get_ref_count.finalize().unwrap();
+            is_root.finalize().unwrap();
+            get_children.finalize().unwrap();
+            dec_ref_count.finalize().unwrap();
+            delete_node.finalize().unwrap();
+            enqueue.finalize().unwrap();
+
+            (scanned, collected)
+        });
+        self.instrumentation.on_gc(scanned, collected, start.elapsed());
+        scanned
     }
 
     /// Implementation of `Clone::clone` for testing `SqlDB::memory` `DB`s concurrently.
@@ -394,6 +707,7 @@ impl<H: WellBehavedHasher> SqlDB<H> {
                 pool: self.pool.clone(),
                 _phantom: self._phantom,
                 lock_file: None,
+                instrumentation: self.instrumentation.clone(),
             },
         }
     }
@@ -452,44 +766,47 @@ impl<H: WellBehavedHasher> DB for SqlDB<H> {
 
     fn get_node(&self, key: &ArenaKey<H>) -> Option<OnDiskObject<H>> {
         let key = key.clone();
-        self.with_tx(Deferred, |tx| {
-            let sql = "SELECT data, ref_count, children FROM node WHERE key = (?1)";
-            let mut stmt = tx.prepare(sql).unwrap();
-            let result = stmt
-                .query_row(params![key], |row| {
-                    let data = row.get(0)?;
-                    let ref_count = row.get(1)?;
-                    let children: Children<H> = row.get(2)?;
-                    let children = children.0;
-                    Ok(OnDiskObject {
-                        data,
-                        ref_count,
-                        children,
+        self.instrument_query(QueryKind::GetNode, || {
+            self.with_tx(Deferred, |tx| {
+                let sql = "SELECT data, ref_count, children FROM node WHERE key = (?1)";
+                let mut stmt = tx.prepare(sql).unwrap();
+                let result = stmt
+                    .query_row(params![key], |row| {
+                        let data = row.get(0)?;
+                        let ref_count = row.get(1)?;
+                        let children: Children<H> = row.get(2)?;
+                        let children = children.0;
+                        Ok(OnDiskObject {
+                            data,
+                            ref_count,
+                            children,
+                        })
                     })
-                })
-                .optional()
-                .unwrap();
-            stmt.finalize().unwrap();
-            result
+                    .optional()
+                    .unwrap();
+                stmt.finalize().unwrap();
+                result
+            })
         })
     }
 
     fn get_unreachable_keys(&self) -> Vec<ArenaKey<H>> {
-        self.with_tx(Deferred, |tx| {
-            // Select keys that are not roots and have a `ref_count` of 0.
-            let sql =
-                "SELECT key FROM node WHERE key NOT IN (SELECT key FROM root) AND ref_count = 0";
-            let mut get_unreachable_keys = tx.prepare(sql).unwrap();
-            let unreachable_keys: Vec<ArenaKey<H>> = get_unreachable_keys
-                .query_map([], |row| {
-                    let key: ArenaKey<H> = row.get(0)?;
-                    Ok(key)
-                })
-                .unwrap()
-                .map(|r| r.unwrap())
-                .collect();
-            get_unreachable_keys.finalize().unwrap();
-            unreachable_keys
+        self.instrument_query(QueryKind::GetUnreachableKeys, || {
+            self.with_tx(Deferred, |tx| {
+                // Select keys that are not roots and have a `ref_count` of 0.
+                let sql = "SELECT key FROM node WHERE key NOT IN (SELECT key FROM root) AND ref_count = 0";
+                let mut get_unreachable_keys = tx.prepare(sql).unwrap();
+                let unreachable_keys: Vec<ArenaKey<H>> = get_unreachable_keys
+                    .query_map([], |row| {
+                        let key: ArenaKey<H> = row.get(0)?;
+                        Ok(key)
+                    })
+                    .unwrap()
+                    .map(|r| r.unwrap())
+                    .collect();
+                get_unreachable_keys.finalize().unwrap();
+                unreachable_keys
+            })
         })
     }
 
@@ -499,58 +816,64 @@ impl<H: WellBehavedHasher> DB for SqlDB<H> {
         I: Iterator<Item = ArenaKey<H>>,
     {
         let keys = keys.collect::<Vec<_>>();
-        self.with_tx(Deferred, |tx| {
-            let sql = "SELECT data, ref_count, children FROM node WHERE key = (?1)";
-            let mut stmt = tx.prepare(sql).unwrap();
-            let result = keys
-                .into_iter()
-                .filter_map(|key| {
-                    stmt.query_row(params![key.clone()], |row| {
-                        let data = row.get(0)?;
-                        let ref_count = row.get(1)?;
-                        let children: Children<H> = row.get(2)?;
-                        let children = children.0;
-                        let obj = OnDiskObject {
-                            data,
-                            ref_count,
-                            children,
-                        };
-                        Ok((key, Some(obj)))
+        self.instrument_query(QueryKind::BatchGetNodes, || {
+            self.with_tx(Deferred, |tx| {
+                let sql = "SELECT data, ref_count, children FROM node WHERE key = (?1)";
+                let mut stmt = tx.prepare(sql).unwrap();
+                let result = keys
+                    .into_iter()
+                    .filter_map(|key| {
+                        stmt.query_row(params![key.clone()], |row| {
+                            let data = row.get(0)?;
+                            let ref_count = row.get(1)?;
+                            let children: Children<H> = row.get(2)?;
+                            let children = children.0;
+                            let obj = OnDiskObject {
+                                data,
+                                ref_count,
+                                children,
+                            };
+                            Ok((key, Some(obj)))
+                        })
+                        .optional()
+                        .unwrap()
                     })
-                    .optional()
-                    .unwrap()
-                })
-                .collect();
-            stmt.finalize().unwrap();
-            result
+                    .collect();
+                stmt.finalize().unwrap();
+                result
+            })
         })
     }
 
     /// Always use `batch_update` instead if you have a lot of keys to insert!
     fn insert_node(&mut self, key: ArenaKey<H>, object: OnDiskObject<H>) {
-        self.with_tx(Immediate, |tx| {
-            let sql = "INSERT OR REPLACE INTO node (key, data, ref_count, children) \
+        self.instrument_query(QueryKind::InsertNode, || {
+            self.with_tx(Immediate, |tx| {
+                let sql = "INSERT OR REPLACE INTO node (key, data, ref_count, children) \
                        VALUES (?1, ?2, ?3, ?4)";
-            let mut stmt = tx.prepare(sql).unwrap();
-            stmt.execute(params![
-                key,
-                object.data,
-                object.ref_count,
-                Children(object.children)
-            ])
-            .unwrap();
-            stmt.finalize().unwrap();
+                let mut stmt = tx.prepare(sql).unwrap();
+                stmt.execute(params![
+                    key,
+                    object.data,
+                    object.ref_count,
+                    Children(object.children)
+                ])
+                .unwrap();
+                stmt.finalize().unwrap();
+            })
         })
     }
 
     /// Always use `batch_update` instead if you have a lot of keys to delete!
     fn delete_node(&mut self, key: &ArenaKey<H>) {
         let key = key.clone();
-        self.with_tx(Immediate, |tx| {
-            let sql = "DELETE FROM node WHERE key = (?1)";
-            let mut stmt = tx.prepare(sql).unwrap();
-            stmt.execute(params![key]).unwrap();
-            stmt.finalize().unwrap();
+        self.instrument_query(QueryKind::DeleteNode, || {
+            self.with_tx(Immediate, |tx| {
+                let sql = "DELETE FROM node WHERE key = (?1)";
+                let mut stmt = tx.prepare(sql).unwrap();
+                stmt.execute(params![key]).unwrap();
+                stmt.finalize().unwrap();
+            })
         })
     }
 
@@ -561,6 +884,8 @@ impl<H: WellBehavedHasher> DB for SqlDB<H> {
         I: Iterator<Item = (ArenaKey<H>, Update<H>)>,
     {
         use Update::*;
+        let start = Instant::now();
+        let mut rows = 0;
         // For batching at the SQL level, this approach is supposed to be faster
         // (and easier!) than building up large INSERTs:
         // https://stackoverflow.com/a/5209093/470844
@@ -594,71 +919,81 @@ impl<H: WellBehavedHasher> DB for SqlDB<H> {
                         }
                     }
                 };
+                rows += 1;
             }
             insert_node.finalize().unwrap();
             delete_node.finalize().unwrap();
             set_root_count.finalize().unwrap();
             delete_root_count.finalize().unwrap();
-        })
+        });
+        self.instrumentation.on_batch_update(rows, start.elapsed());
     }
 
     fn size(&self) -> usize {
-        self.with_tx(Deferred, |tx| {
-            let sql = "SELECT COUNT(*) FROM node";
-            let mut stmt = tx.prepare(sql).unwrap();
-            let result = stmt.query_row([], |row| row.get(0)).unwrap();
-            stmt.finalize().unwrap();
-            result
+        self.instrument_query(QueryKind::Size, || {
+            self.with_tx(Deferred, |tx| {
+                let sql = "SELECT COUNT(*) FROM node";
+                let mut stmt = tx.prepare(sql).unwrap();
+                let result = stmt.query_row([], |row| row.get(0)).unwrap();
+                stmt.finalize().unwrap();
+                result
+            })
         })
     }
 
     fn get_root_count(&self, key: &ArenaKey<Self::Hasher>) -> u32 {
         let key = key.clone();
-        self.with_tx(Deferred, |tx| {
-            let sql = "SELECT count FROM root WHERE key = (?1)";
-            let mut stmt = tx.prepare(sql).unwrap();
-            let result = stmt
-                .query_row(params![key], |row| row.get(0))
-                .optional()
-                .unwrap()
-                .unwrap_or(0);
-            stmt.finalize().unwrap();
-            result
+        self.instrument_query(QueryKind::GetRootCount, || {
+            self.with_tx(Deferred, |tx| {
+                let sql = "SELECT count FROM root WHERE key = (?1)";
+                let mut stmt = tx.prepare(sql).unwrap();
+                let result = stmt
+                    .query_row(params![key], |row| row.get(0))
+                    .optional()
+                    .unwrap()
+                    .unwrap_or(0);
+                stmt.finalize().unwrap();
+                result
+            })
         })
     }
 
     fn set_root_count(&mut self, key: ArenaKey<Self::Hasher>, count: u32) {
-        self.with_tx(Immediate, |tx| {
-            if count > 0 {
-                let sql = "INSERT OR REPLACE INTO root (key, count) \
+        self.instrument_query(QueryKind::SetRootCount, || {
+            self.with_tx(Immediate, |tx| {
+                if count > 0 {
+                    let sql = "INSERT OR REPLACE INTO root (key, count) \
                        VALUES (?1, ?2)";
-                let mut stmt = tx.prepare(sql).unwrap();
-                stmt.execute(params![key, count]).unwrap();
-                stmt.finalize().unwrap();
-            } else {
-                let sql = "DELETE FROM root WHERE key = (?1)";
-                let mut stmt = tx.prepare(sql).unwrap();
-                stmt.execute(params![key]).unwrap();
-                stmt.finalize().unwrap();
-            }
+                    let mut stmt = tx.prepare(sql).unwrap();
+                    stmt.execute(params![key, count]).unwrap();
+                    stmt.finalize().unwrap();
+                } else {
+                    let sql = "DELETE FROM root WHERE key = (?1)";
+                    let mut stmt = tx.prepare(sql).unwrap();
+                    stmt.execute(params![key]).unwrap();
+                    stmt.finalize().unwrap();
+                }
+            })
         })
     }
 
     fn get_roots(&self) -> HashMap<ArenaKey<Self::Hasher>, u32> {
-        self.with_tx(Deferred, |tx| {
-            let sql = "SELECT key, count FROM root";
-            let mut stmt = tx.prepare(sql).unwrap();
-            let result = stmt
-                .query_map([], |row| {
-                    let key: ArenaKey<H> = row.get(0)?;
-                    let count: u32 = row.get(1)?;
-                    Ok((key, count))
-                })
-                .unwrap()
-                .map(|r| r.unwrap())
-                .collect();
-            stmt.finalize().unwrap();
-            result
+        self.instrument_query(QueryKind::GetRoots, || {
+            self.with_tx(Deferred, |tx| {
+                let sql = "SELECT key, count FROM root";
+                let mut stmt = tx.prepare(sql).unwrap();
+                let result = stmt
+                    .query_map([], |row| {
+                        let key: ArenaKey<H> = row.get(0)?;
+                        let count: u32 = row.get(1)?;
+                        Ok((key, count))
+                    })
+                    .unwrap()
+                    .map(|r| r.unwrap())
+                    .collect();
+                stmt.finalize().unwrap();
+                result
+            })
         })
     }
 }
@@ -678,12 +1013,14 @@ impl<H: WellBehavedHasher> Arbitrary for SqlDB<H> {
 
 #[cfg(test)]
 mod tests {
-    use super::{SqlDB, Update::*};
+    use super::{Instrumentation, QueryKind, SqlDB, Update::*};
     use crate::{DefaultHasher, WellBehavedHasher, arena::ArenaKey, backend::OnDiskObject, db::DB};
     use rand::Rng;
     use rusqlite::TransactionBehavior::Deferred;
     use rusqlite::types::FromSql;
     use std::collections::HashSet;
+    use std::sync::Mutex;
+    use std::time::Duration;
 
     /// This test always fails due to db locking errors. Since we don't intend
     /// to use the memory back-end anyway, not going to fix this.
@@ -844,6 +1181,231 @@ mod tests {
         assert!(db.get_node(&n5.key).is_some());
     }
 
+    /// Run incremental GC to completion, one `budget`-sized step at a time.
+    fn run_incremental_gc(
+        db: &mut SqlDB,
+        additional_roots: &HashSet<ArenaKey<DefaultHasher>>,
+        budget: usize,
+    ) {
+        db.gc_seed_worklist(additional_roots);
+        while db.gc_step(budget) > 0 {}
+    }
+
+    /// Test the incremental GC mode (`gc_seed_worklist` + `gc_step`), checking
+    /// that it converges to the same result as `_gc`, for various budgets.
+    #[test]
+    fn incremental_gc() {
+        use crate::backend::raw_node::RawNode;
+
+        let n5 = RawNode::new(&[5], 2, vec![]);
+        let n4 = RawNode::new(&[4], 1, vec![&n5]);
+        let n3 = RawNode::new(&[3], 1, vec![&n5]);
+        let n2 = RawNode::new(&[2], 1, vec![&n4, &n3]);
+        let n1 = RawNode::new(&[1], 0, vec![&n2]);
+        let nodes: [&RawNode; 5] = [&n5, &n4, &n3, &n2, &n1];
+
+        let init_db = || {
+            let mut db = SqlDB::default();
+            for n in nodes.iter() {
+                n.insert_into_db(&mut db);
+            }
+            db
+        };
+
+        // A small budget means `gc_step` runs several times per `_gc`-worth
+        // of work; a large budget processes every pending key in each step,
+        // but children enqueued during a step are only processed by the next
+        // step, so even then the sweep takes several steps.
+        for budget in [1, 2, 100] {
+            let mut db = init_db();
+            db.set_root_count(n1.key.clone(), 1);
+            run_incremental_gc(&mut db, &HashSet::new(), budget);
+            for n in nodes.iter() {
+                assert!(db.get_node(&n.key).is_some());
+            }
+            db.set_root_count(n1.key.clone(), 0);
+            run_incremental_gc(&mut db, &HashSet::new(), budget);
+            assert_eq!(db.size(), 0);
+
+            ////////////////////////////////////////////////////////////////
+
+            let mut db = init_db();
+
+            db.set_root_count(n2.key.clone(), 1);
+            run_incremental_gc(&mut db, &HashSet::new(), budget);
+            assert!(db.get_node(&n1.key).is_none());
+            assert!(db.get_node(&n2.key).is_some());
+            assert!(db.get_node(&n3.key).is_some());
+            assert!(db.get_node(&n4.key).is_some());
+            assert!(db.get_node(&n5.key).is_some());
+
+            db.set_root_count(n2.key.clone(), 0);
+            db.set_root_count(n3.key.clone(), 1);
+            run_incremental_gc(&mut db, &HashSet::new(), budget);
+            assert!(db.get_node(&n1.key).is_none());
+            assert!(db.get_node(&n2.key).is_none());
+            assert!(db.get_node(&n3.key).is_some());
+            assert!(db.get_node(&n4.key).is_none());
+            assert!(db.get_node(&n5.key).is_some());
+
+            db.set_root_count(n3.key.clone(), 0);
+            run_incremental_gc(&mut db, &HashSet::new(), budget);
+            assert_eq!(db.size(), 0);
+
+            ////////////////////////////////////////////////////////////////
+
+            let mut db = init_db();
+            let additional_roots = [n3.key.clone(), n4.key.clone()].into_iter().collect();
+            run_incremental_gc(&mut db, &additional_roots, budget);
+            assert!(db.get_node(&n1.key).is_none());
+            assert!(db.get_node(&n2.key).is_none());
+            assert!(db.get_node(&n3.key).is_some());
+            assert!(db.get_node(&n4.key).is_some());
+            assert!(db.get_node(&n5.key).is_some());
+        }
+    }
+
+    /// An enqueued-but-not-yet-processed key can be re-referenced by normal
+    /// operation interleaved between `gc_step` calls; `gc_step` must re-check
+    /// it at processing time and leave it (and its children) alone rather
+    /// than trusting the state it had when it was enqueued.
+    #[test]
+    fn incremental_gc_interleaved_write() {
+        use crate::backend::raw_node::RawNode;
+
+        let n5 = RawNode::new(&[5], 2, vec![]);
+        let n4 = RawNode::new(&[4], 1, vec![&n5]);
+        let n3 = RawNode::new(&[3], 1, vec![&n5]);
+        let n2 = RawNode::new(&[2], 1, vec![&n4, &n3]);
+        let n1 = RawNode::new(&[1], 0, vec![&n2]);
+        let nodes: [&RawNode; 5] = [&n5, &n4, &n3, &n2, &n1];
+
+        let mut db = SqlDB::default();
+        for n in nodes.iter() {
+            n.insert_into_db(&mut db);
+        }
+
+        // Seed the worklist with n1 (the only initially unreferenced key),
+        // then step once with a budget of 1: n1 is deleted, n2's ref_count
+        // drops to zero, and n2 is enqueued -- but not yet processed.
+        db.gc_seed_worklist(&HashSet::new());
+        assert_eq!(db.gc_step(1), 1);
+        assert!(db.get_node(&n1.key).is_none());
+        assert!(db.get_node(&n2.key).is_some());
+
+        // Normal operation interleaves here and re-references n2, e.g. by
+        // rooting it, before `gc_step` gets around to processing it.
+        db.set_root_count(n2.key.clone(), 1);
+
+        // The next step pops n2, finds it's now rooted, and leaves it (and
+        // its children) alone instead of collecting it.
+        assert_eq!(db.gc_step(100), 1);
+        assert!(db.get_node(&n2.key).is_some());
+        assert!(db.get_node(&n3.key).is_some());
+        assert!(db.get_node(&n4.key).is_some());
+        assert!(db.get_node(&n5.key).is_some());
+
+        // The worklist is now fully drained.
+        assert_eq!(db.gc_step(100), 0);
+    }
+
+    ////////////////////////////////////////////////////////////////
+    // Tests for `Instrumentation`.
+
+    /// An `Instrumentation` that records the arguments (other than the
+    /// duration) of every callback invocation, in order.
+    #[derive(Default)]
+    struct CountingInstrumentation {
+        batch_updates: Mutex<Vec<usize>>,
+        gcs: Mutex<Vec<(usize, usize)>>,
+        queries: Mutex<Vec<QueryKind>>,
+    }
+
+    impl Instrumentation for CountingInstrumentation {
+        fn on_batch_update(&self, rows: usize, _duration: Duration) {
+            self.batch_updates.lock().unwrap().push(rows);
+        }
+
+        fn on_gc(&self, scanned: usize, collected: usize, _duration: Duration) {
+            self.gcs.lock().unwrap().push((scanned, collected));
+        }
+
+        fn on_query(&self, kind: QueryKind, _duration: Duration) {
+            self.queries.lock().unwrap().push(kind);
+        }
+    }
+
+    #[test]
+    fn instrumentation_hooks() {
+        use crate::backend::raw_node::RawNode;
+        use std::sync::Arc;
+
+        let n2 = RawNode::new(&[2], 1, vec![]);
+        let n1 = RawNode::new(&[1], 0, vec![&n2]);
+
+        let instrumentation = Arc::new(CountingInstrumentation::default());
+        let mut db = SqlDB::default();
+        db.set_instrumentation(instrumentation.clone());
+
+        db.insert_node(n1.key.clone(), n1.clone().into_obj());
+        assert_eq!(*instrumentation.queries.lock().unwrap(), vec![QueryKind::InsertNode]);
+
+        db.get_node(&n1.key);
+        assert_eq!(
+            *instrumentation.queries.lock().unwrap(),
+            vec![QueryKind::InsertNode, QueryKind::GetNode]
+        );
+
+        let iter = [(n2.key.clone(), InsertNode(n2.clone().into_obj()))].into_iter();
+        db.batch_update(iter);
+        assert_eq!(*instrumentation.batch_updates.lock().unwrap(), vec![1]);
+
+        db.set_root_count(n1.key.clone(), 1);
+        db._gc(HashSet::new());
+        assert_eq!(*instrumentation.gcs.lock().unwrap(), vec![(0, 0)]);
+
+        db.set_root_count(n1.key.clone(), 0);
+        db._gc(HashSet::new());
+        assert_eq!(*instrumentation.gcs.lock().unwrap(), vec![(0, 0), (2, 2)]);
+    }
+
+    /// `gc_seed_worklist` and `gc_step`, like `_gc`, report through `on_gc`.
+    #[test]
+    fn incremental_gc_instrumentation() {
+        use crate::backend::raw_node::RawNode;
+        use std::sync::Arc;
+
+        let n2 = RawNode::new(&[2], 1, vec![]);
+        let n1 = RawNode::new(&[1], 0, vec![&n2]);
+
+        let instrumentation = Arc::new(CountingInstrumentation::default());
+        let mut db = SqlDB::default();
+        db.set_instrumentation(instrumentation.clone());
+
+        n1.insert_into_db(&mut db);
+        n2.insert_into_db(&mut db);
+
+        // Seeding finds n1 (the only unreferenced key) and enqueues it;
+        // nothing is collected yet.
+        db.gc_seed_worklist(&HashSet::new());
+        assert_eq!(*instrumentation.gcs.lock().unwrap(), vec![(1, 0)]);
+
+        // Pops n1, collects it, and enqueues n2 (now unreferenced).
+        assert_eq!(db.gc_step(100), 1);
+        assert_eq!(*instrumentation.gcs.lock().unwrap(), vec![(1, 0), (1, 1)]);
+
+        // Pops n2 and collects it too.
+        assert_eq!(db.gc_step(100), 1);
+        assert_eq!(*instrumentation.gcs.lock().unwrap(), vec![(1, 0), (1, 1), (1, 1)]);
+
+        // The worklist is now drained: nothing popped, nothing collected.
+        assert_eq!(db.gc_step(100), 0);
+        assert_eq!(
+            *instrumentation.gcs.lock().unwrap(),
+            vec![(1, 0), (1, 1), (1, 1), (0, 0)]
+        );
+    }
+
     ////////////////////////////////////////////////////////////////
     // Tests for exclusive and shared locking.
 